@@ -1,8 +1,20 @@
 //! This crate provides a `Token` that can be used to co-operatively
 //! signal when an operation should be canceled.
 //!
+//! Cancellation authority and observation are split into two types: a
+//! `TokenSource` owns the ability to cancel (and arm deadlines), and
+//! vends cheap, cloneable `Token` observers via `TokenSource::token`.
+//! Code that merely participates in an operation should take a `Token`
+//! and can only ask whether it has been canceled; only the code that
+//! owns the operation's lifecycle should hold the `TokenSource`.
+//!
+//! Cancellation itself has two severities: `cancel_graceful` asks an
+//! operation to wind down cleanly, while `cancel` (forced) asks it to
+//! stop immediately.  Escalation is monotonic: once forced, a token
+//! can't go back to merely graceful.
+//!
 //! ```rust
-//! use cancel::{Canceled, Token};
+//! use cancel::{Canceled, Token, TokenSource};
 //! use std::time::Duration;
 //!
 //! fn do_something(token: &Token) -> Result<bool, Canceled> {
@@ -16,14 +28,22 @@
 //! }
 //!
 //! fn start_something() -> Result<bool, Canceled> {
-//!   let token = Token::with_duration(Duration::new(10, 0));
-//!   do_something(&token)
+//!   let source = TokenSource::with_duration(Duration::new(10, 0));
+//!   do_something(&source.token())
 //! }
 //! ```
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
+/// No cancellation has been requested.
+const RUNNING: u8 = 0;
+/// A graceful wind-down has been requested; in-flight work may finish.
+const GRACEFUL: u8 = 1;
+/// Immediate cancellation has been requested.
+const FORCED: u8 = 2;
+
 /// The Err value returned from `Token::check_cancel`.
 /// It indicates that the `Token` was canceled and that the operation
 /// should cease.
@@ -37,55 +57,487 @@ impl std::fmt::Display for Canceled {
     }
 }
 
-/// A cancellation token.
-/// It tracks the state and holds an optional deadline for the operation.
-/// To share `Token` across threads, wrap it in a `std::sync::Arc`.
+/// The shared state behind a `TokenSource`/`Token` pair.  This is
+/// wrapped in an `Arc` so that a child source can cheaply hold a
+/// reference to its parent's state without the parent needing to track
+/// its children, and so that `Token` observers can be cloned cheaply.
+#[derive(Default)]
+struct Inner {
+    /// One of `RUNNING`, `GRACEFUL` or `FORCED`.  Only ever escalates.
+    level: AtomicU8,
+    graceful_deadline: Mutex<Option<Instant>>,
+    forced_deadline: Mutex<Option<Instant>>,
+    /// The source that this one was derived from, if any.  A child is
+    /// considered canceled if it, or any of its ancestors, are canceled.
+    parent: Option<Arc<Inner>>,
+    /// If this token was produced by `Token::any`/`Token::all`, the
+    /// combinator that derives its level from a set of input tokens.
+    combinator: Option<Combinator>,
+    /// Closures to run exactly once, at the moment this token becomes
+    /// canceled (forced).  Drained (rather than just iterated) by
+    /// `escalate` so that a closure can never be invoked twice.
+    on_cancel: Mutex<Vec<Box<dyn FnOnce() + Send>>>,
+    /// Wakes any `Token::cancelled()` futures waiting on this token.
+    /// Only present with the `async` feature enabled, so that the
+    /// default build stays dependency-free and signal safe.
+    #[cfg(feature = "async")]
+    notify: tokio::sync::Notify,
+}
+
+impl std::fmt::Debug for Inner {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Inner")
+            .field("level", &self.level)
+            .field("graceful_deadline", &self.graceful_deadline)
+            .field("forced_deadline", &self.forced_deadline)
+            .field("parent", &self.parent)
+            .field("combinator", &self.combinator)
+            .finish()
+    }
+}
+
+/// How a token produced by `Token::any`/`Token::all` derives its level
+/// and effective forced deadline from a set of input tokens.  Evaluated
+/// lazily on each poll, mirroring how a child token derives its level
+/// from its `parent` rather than being notified of it.
+#[derive(Debug, Clone)]
+enum Combinator {
+    /// Canceled to a given level as soon as any input reaches it; the
+    /// effective forced deadline is the earliest of the inputs'.
+    Any(Vec<Token>),
+    /// Canceled to a given level only once every input has reached it;
+    /// the effective forced deadline is the latest of the inputs', since
+    /// all of them must expire.
+    All(Vec<Token>),
+}
+
+impl Combinator {
+    fn effective_level(&self) -> u8 {
+        match self {
+            Combinator::Any(tokens) => tokens
+                .iter()
+                .map(|t| t.inner.effective_level())
+                .max()
+                .unwrap_or(RUNNING),
+            Combinator::All(tokens) => tokens
+                .iter()
+                .map(|t| t.inner.effective_level())
+                .min()
+                .unwrap_or(RUNNING),
+        }
+    }
+
+    /// Only reachable via `Inner::effective_forced_deadline`, which is
+    /// itself gated behind `async`; gate this the same way so the
+    /// default build stays warning-clean.
+    #[cfg(feature = "async")]
+    fn effective_forced_deadline(&self) -> Option<Instant> {
+        match self {
+            Combinator::Any(tokens) => tokens
+                .iter()
+                .filter_map(|t| t.inner.effective_forced_deadline())
+                .min(),
+            Combinator::All(tokens) => {
+                let mut latest = None;
+                for token in tokens {
+                    let deadline = token.inner.effective_forced_deadline()?;
+                    latest = Some(latest.map_or(deadline, |d: Instant| d.max(deadline)));
+                }
+                latest
+            }
+        }
+    }
+}
+
+impl Inner {
+    fn new(
+        graceful_deadline: Option<Instant>,
+        forced_deadline: Option<Instant>,
+        parent: Option<Arc<Inner>>,
+    ) -> Self {
+        Self {
+            level: AtomicU8::new(RUNNING),
+            graceful_deadline: Mutex::new(graceful_deadline),
+            forced_deadline: Mutex::new(forced_deadline),
+            parent,
+            combinator: None,
+            on_cancel: Mutex::new(Vec::new()),
+            #[cfg(feature = "async")]
+            notify: tokio::sync::Notify::new(),
+        }
+    }
+
+    /// The effective forced deadline for this token, taking into
+    /// account any ancestors: a child can never outlive its parent's
+    /// deadline, so this is the earliest one found while walking up
+    /// the chain.  A token produced by `Token::any`/`Token::all` also
+    /// folds in the deadline derived from its combinator.
+    ///
+    /// Only used by `Token::cancelled()` to pick a wakeup time, so this
+    /// is gated behind the `async` feature to keep the default,
+    /// dependency-free build warning-clean.
+    #[cfg(feature = "async")]
+    fn effective_forced_deadline(&self) -> Option<Instant> {
+        let mut deadline = *self.forced_deadline.lock().unwrap();
+        if let Some(parent) = &self.parent {
+            deadline = match (deadline, parent.effective_forced_deadline()) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (Some(a), None) => Some(a),
+                (None, b) => b,
+            };
+        }
+        if let Some(combinator) = &self.combinator {
+            deadline = match (deadline, combinator.effective_forced_deadline()) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (Some(a), None) => Some(a),
+                (None, b) => b,
+            };
+        }
+        deadline
+    }
+
+    /// Arm (or replace) the forced deadline on this node only.
+    fn arm_forced_deadline(&self, deadline: Instant) {
+        *self.forced_deadline.lock().unwrap() = Some(deadline);
+    }
+
+    /// Escalate this node's own level to at least `level`, running the
+    /// `on_cancel` callbacks the first time the level reaches `FORCED`.
+    /// Monotonic: escalating to a lower level than the current one is a
+    /// no-op, thanks to `fetch_max`.
+    fn escalate(&self, level: u8) {
+        let previous = self.level.fetch_max(level, Ordering::AcqRel);
+        if previous < FORCED && level >= FORCED {
+            self.run_on_cancel();
+            #[cfg(feature = "async")]
+            self.notify.notify_waiters();
+        }
+    }
+
+    /// Escalate this node if either of its own deadlines has passed.
+    fn poll_deadlines(&self) {
+        let now = Instant::now();
+        if let Some(deadline) = *self.forced_deadline.lock().unwrap() {
+            if now > deadline {
+                self.escalate(FORCED);
+            }
+        }
+        if let Some(deadline) = *self.graceful_deadline.lock().unwrap() {
+            if now > deadline {
+                self.escalate(GRACEFUL);
+            }
+        }
+    }
+
+    /// The effective level of this token: its own level (after polling
+    /// its own deadlines) combined with that of its ancestors, since a
+    /// token is at least as canceled as any of its ancestors, and with
+    /// that of its combinator, if this token was produced by
+    /// `Token::any`/`Token::all`.  If that combined level is higher than
+    /// this node's own, the node is escalated to it, so that its own
+    /// `on_cancel` callbacks run once the cancellation is noticed here.
+    fn effective_level(&self) -> u8 {
+        self.poll_deadlines();
+        let own = self.level.load(Ordering::Acquire);
+        let mut level = own;
+        if let Some(parent) = &self.parent {
+            level = level.max(parent.effective_level());
+        }
+        if let Some(combinator) = &self.combinator {
+            level = level.max(combinator.effective_level());
+        }
+        if level > own {
+            self.escalate(level);
+        }
+        level
+    }
+
+    /// Run and forget any callbacks registered via `Token::on_cancel`.
+    /// The vec is drained up front so that a callback which happens to
+    /// register another one doesn't deadlock on `on_cancel`'s lock, and
+    /// so each callback runs at most once even under concurrent calls.
+    fn run_on_cancel(&self) {
+        let callbacks = std::mem::take(&mut *self.on_cancel.lock().unwrap());
+        for callback in callbacks {
+            callback();
+        }
+    }
+}
+
+/// Holds the authority to cancel an operation.
+/// A `TokenSource` owns the lifecycle of a cancellation: it is the only
+/// way to call `cancel()` or arm a deadline, while the cheap, cloneable
+/// `Token` handles vended by `TokenSource::token` can only observe
+/// whether cancellation has happened.  This prevents a worker that
+/// merely holds a `Token` from accidentally canceling the operation for
+/// everyone else.
+/// To share a `TokenSource` across threads, wrap it in a `std::sync::Arc`.
 #[derive(Debug, Default)]
-pub struct Token {
-    canceled: AtomicBool,
-    deadline: Option<Instant>,
+pub struct TokenSource {
+    inner: Arc<Inner>,
 }
 
-impl Token {
-    /// Create a new Token with no deadline.  The token
-    /// will be marked as canceled only once `Token::cancel`
-    /// has been called.
+impl TokenSource {
+    /// Create a new TokenSource with no deadline.  Its tokens will be
+    /// marked as canceled only once `TokenSource::cancel` (or
+    /// `cancel_graceful`) has been called.
     pub fn new() -> Self {
         Default::default()
     }
 
-    /// Create a new Token with a deadline set to the current
-    /// clock plus the specified duration.  The token will be
-    /// marked as canceled either when `Token::cancel` is
+    /// Create a new TokenSource with a forced deadline set to the
+    /// current clock plus the specified duration.  Its tokens will be
+    /// marked as (forced) canceled either when `TokenSource::cancel` is
     /// called, or when the operation calls either `Token::is_canceled`
-    /// or `Token::check_cancel` and the current clock exceeds
-    /// the computed deadline.
+    /// or `Token::check_cancel` and the current clock exceeds the
+    /// computed deadline.  Use `with_durations` to also set a graceful
+    /// deadline.
     pub fn with_duration(duration: Duration) -> Self {
-        Self {
-            canceled: AtomicBool::new(false),
-            deadline: Some(Instant::now() + duration),
-        }
+        Self::with_deadline(Instant::now() + duration)
     }
 
-    /// Create a new Token with a deadline set to the specified
-    /// instant.  The token will be marked as canceled either when
-    /// `Token::cancel` is called, or when the operation calls
-    /// either `Token::is_canceled` or `Token::check_cancel` and
-    /// the current clock exceeds the specified deadline.
+    /// Create a new TokenSource with a forced deadline set to the
+    /// specified instant.  See `with_duration`.
     pub fn with_deadline(deadline: Instant) -> Self {
+        Self::with_deadlines(None, Some(deadline))
+    }
+
+    /// Create a new TokenSource with both a graceful and a forced
+    /// deadline, each set to the current clock plus the respective
+    /// duration.  Its tokens become graceful-canceled once `graceful`
+    /// elapses, and forced-canceled once `forced` elapses (or sooner,
+    /// via an explicit `cancel_graceful`/`cancel` call).
+    pub fn with_durations(graceful: Duration, forced: Duration) -> Self {
+        let now = Instant::now();
+        Self::with_deadlines(Some(now + graceful), Some(now + forced))
+    }
+
+    /// Create a new TokenSource with the specified graceful and/or
+    /// forced deadlines.  See `with_durations`.
+    pub fn with_deadlines(graceful: Option<Instant>, forced: Option<Instant>) -> Self {
         Self {
-            canceled: AtomicBool::new(false),
-            deadline: Some(deadline),
+            inner: Arc::new(Inner::new(graceful, forced, None)),
+        }
+    }
+
+    /// Derive a child source from this one.  The child's tokens are
+    /// canceled whenever the child is explicitly canceled, its own
+    /// deadline (if any) passes, or this source (or one of its own
+    /// ancestors) becomes canceled.  Canceling a child has no effect on
+    /// its parent.  A child's effective deadline is the earlier of its
+    /// own deadline and its parent's, so it can never outlive its
+    /// parent.
+    ///
+    /// There is no bookkeeping kept in the parent for its children;
+    /// cancellation is discovered lazily by walking the chain of
+    /// ancestors each time a token is checked.
+    pub fn child_token_source(&self) -> TokenSource {
+        TokenSource {
+            inner: Arc::new(Inner::new(None, None, Some(Arc::clone(&self.inner)))),
         }
     }
 
-    /// Explicitly mark the token as being canceled.
-    /// This method is async signal safe.
+    /// Vend a cheap, cloneable, read-only `Token` observer tied to this
+    /// source.  Holders of the `Token` can ask whether the operation
+    /// has been canceled, but cannot cancel it themselves.
+    pub fn token(&self) -> Token {
+        Token {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    /// Ask this source's tokens to wind down gracefully: workers should
+    /// stop accepting new work, but may finish in-flight items and run
+    /// cleanup.  This only escalates towards `FORCED`, never back down;
+    /// calling it after `cancel()` has no effect.
+    pub fn cancel_graceful(&self) {
+        self.inner.escalate(GRACEFUL);
+    }
+
+    /// Explicitly mark this source's tokens as being (forced) canceled:
+    /// workers should abort immediately.
+    ///
+    /// The atomic store itself is async signal safe, but running any
+    /// closures registered via `Token::on_cancel` is not, since it may
+    /// allocate or block on the `on_cancel` lock.  A signal handler
+    /// that only needs to flip the flag (and leaves observers to poll
+    /// `is_canceled`) remains safe to call this from; don't register
+    /// `on_cancel` callbacks on a token whose source a signal handler
+    /// cancels.
     pub fn cancel(&self) {
-        self.canceled.store(true, Ordering::Release);
+        self.inner.escalate(FORCED);
+    }
+
+    /// Arm (or re-arm) a forced deadline set to the current clock plus
+    /// the specified duration.  Unlike `with_duration`, this can be
+    /// called at any point in the source's lifetime, letting the owner
+    /// decide dynamically that an operation should now be bounded.
+    /// This supersedes any previously armed forced deadline on this
+    /// source.
+    pub fn cancel_after(&self, duration: Duration) {
+        self.inner.arm_forced_deadline(Instant::now() + duration);
+    }
+
+    /// Consume this source and return a RAII guard that calls
+    /// `cancel()` when it is dropped.  This ties the cancellation to a
+    /// scope, so that an early return or a panic automatically cancels
+    /// dependent work.  Call `DropGuard::disarm` on the success path to
+    /// get the source back without canceling it.
+    pub fn drop_guard(self) -> DropGuard {
+        DropGuard { source: Some(self) }
+    }
+}
+
+/// A cheap, cloneable, read-only handle on a cancellation.
+/// Holders can observe whether the operation has been canceled, but
+/// only a `TokenSource` can actually cancel it; see `TokenSource::token`.
+#[derive(Debug, Clone, Default)]
+pub struct Token {
+    inner: Arc<Inner>,
+}
+
+impl Token {
+    /// Derive a child token from this one.  The child is canceled
+    /// whenever this token (or one of its ancestors) becomes canceled.
+    /// A plain `Token` can't itself be canceled; if the child needs its
+    /// own cancellation authority, derive a child `TokenSource` from
+    /// the owning source instead.
+    ///
+    /// There is no bookkeeping kept in the parent for its children;
+    /// cancellation is discovered lazily by walking the chain of
+    /// ancestors each time the child is checked.
+    pub fn child_token(&self) -> Token {
+        Token {
+            inner: Arc::new(Inner::new(None, None, Some(Arc::clone(&self.inner)))),
+        }
+    }
+
+    /// Combine several tokens into one derived token that is canceled
+    /// (to a given level) as soon as any of `tokens` reaches it, and
+    /// whose effective forced deadline is the earliest of theirs.  This
+    /// is handy when an operation should stop if either the caller hits
+    /// cancel or some other, unrelated token (say, a global shutdown
+    /// token) fires.
+    ///
+    /// Like a child token, the derived token is evaluated lazily: each
+    /// call to `is_canceled` (or similar) re-checks `tokens` rather than
+    /// being woken up when one of them cancels, so the same caveat about
+    /// `cancelled()` applies if cancellation is discovered solely
+    /// through one of `tokens` rather than through this token's own
+    /// `cancel()`/deadline.  An empty slice is never canceled.
+    pub fn any(tokens: &[Token]) -> Token {
+        Token {
+            inner: Arc::new(Inner {
+                combinator: Some(Combinator::Any(tokens.to_vec())),
+                ..Inner::new(None, None, None)
+            }),
+        }
+    }
+
+    /// Combine several tokens into one derived token that is canceled
+    /// (to a given level) only once every one of `tokens` has reached
+    /// it, and whose effective forced deadline is the latest of theirs,
+    /// since all of them must expire before this token does.
+    ///
+    /// See `Token::any` for the combinator's lazy, poll-based evaluation.
+    /// An empty slice is never canceled.
+    pub fn all(tokens: &[Token]) -> Token {
+        Token {
+            inner: Arc::new(Inner {
+                combinator: Some(Combinator::All(tokens.to_vec())),
+                ..Inner::new(None, None, None)
+            }),
+        }
     }
 
-    /// Check whether the token was canceled.
+    /// Register a closure to run exactly once, at the moment this token
+    /// becomes (forced) canceled.  If the token is already canceled,
+    /// `f` runs immediately, on the calling thread.  Otherwise it is
+    /// stored and invoked later from within `cancel()`, or from
+    /// `is_canceled()` if cancellation is instead discovered via
+    /// deadline expiry.  A graceful cancellation alone does not run
+    /// these callbacks; use `is_graceful_canceled` to observe that.
+    ///
+    /// This is useful for waking a condvar, aborting a socket, or
+    /// otherwise releasing a resource without polling `is_canceled` in
+    /// a tight loop.
+    ///
+    /// Note that this only fires promptly for this token's own
+    /// `cancel()`/deadline; if the token is a child (see `child_token`)
+    /// or was produced by `Token::any`/`Token::all`, and is canceled
+    /// solely because an ancestor or combinator input was canceled, `f`
+    /// is not invoked until something else causes this token to notice,
+    /// e.g. a subsequent `is_canceled()` call — the parent keeps no
+    /// bookkeeping on its children, so there is nothing to wake this
+    /// token's callbacks eagerly.  This mirrors the same caveat on
+    /// `cancelled()`; if you need a prompt, ancestor-driven wakeup,
+    /// register `on_cancel` (or poll `is_canceled`) directly on the
+    /// token that is actually canceled instead of on a derived one.
+    pub fn on_cancel<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if self.is_canceled() {
+            f();
+            return;
+        }
+        // Re-check the level once we hold the lock: `cancel` may have
+        // run (and drained the vec) between the check above and here.
+        let mut callbacks = self.inner.on_cancel.lock().unwrap();
+        if self.inner.level.load(Ordering::Acquire) >= FORCED {
+            drop(callbacks);
+            f();
+        } else {
+            callbacks.push(Box::new(f));
+        }
+    }
+
+    /// Returns a future that resolves once this token becomes (forced)
+    /// canceled, whether via an explicit `cancel()` on its source or
+    /// because its (possibly parent-bounded) forced deadline was
+    /// reached.  If the token is already canceled at the time it is
+    /// awaited, the future resolves immediately.  This lets async
+    /// callers `select!` on cancellation instead of polling
+    /// `is_canceled()` in a loop.
+    ///
+    /// Requires the `async` cargo feature, which pulls in `tokio`; the
+    /// rest of this crate stays dependency-free and signal safe without
+    /// it.  Note that this only reacts promptly to this token's own
+    /// `cancel()`/deadline; if a token is canceled solely because an
+    /// ancestor (see `child_token`) was canceled, the future still
+    /// resolves correctly but may not wake until something else causes
+    /// it to be polled again, since the parent has no way to notify
+    /// children it doesn't track.
+    #[cfg(feature = "async")]
+    pub async fn cancelled(&self) {
+        loop {
+            // Register (and `enable`) the waiter *before* checking
+            // `is_canceled`, per `Notify`'s documented wait-for-condition
+            // pattern: `notify_waiters` wakes only already-registered
+            // waiters and stores no permit, so checking first would let
+            // a `cancel()` landing in the gap between the check and the
+            // registration go unseen, parking this future forever.
+            let notified = self.inner.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            if self.is_canceled() {
+                return;
+            }
+
+            match self.inner.effective_forced_deadline() {
+                Some(deadline) => {
+                    tokio::select! {
+                        _ = &mut notified => {}
+                        _ = tokio::time::sleep_until(deadline.into()) => {}
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+
+    /// Check whether the token was (forced) canceled.
     /// This method is intended to be called by code that initiated
     /// (rather than performed) an operation to test whether that
     /// operation was successful.
@@ -95,28 +547,27 @@ impl Token {
     /// Using `Token::check_cancel` to propagate a `Result` value
     /// is often a cleaner design than using `Token::was_canceled`.
     pub fn was_canceled(&self) -> bool {
-        self.canceled.load(Ordering::Acquire)
+        self.inner.effective_level() >= FORCED
     }
 
-    /// Test whether an ongoing operation should cease
-    /// due to cancellation.
+    /// Test whether this token has been asked to wind down gracefully,
+    /// either directly or because an ancestor has.  A token that has
+    /// been forced-canceled is also considered graceful-canceled, since
+    /// forced is a strictly greater severity.
+    pub fn is_graceful_canceled(&self) -> bool {
+        self.inner.effective_level() >= GRACEFUL
+    }
+
+    /// Test whether an ongoing operation should cease immediately due
+    /// to (forced) cancellation.
     /// If a deadline has been set, the current clock will be evaluated
     /// and compared against the deadline, setting the state to canceled
-    /// if appropriate.
+    /// if appropriate.  A token with ancestors is also considered
+    /// canceled if any ancestor is canceled, or if the effective
+    /// (parent-bounded) deadline has passed.
     /// Returns true if the operation has been canceled.
     pub fn is_canceled(&self) -> bool {
-        if self.was_canceled() {
-            true
-        } else if let Some(deadline) = self.deadline.as_ref() {
-            if Instant::now() > *deadline {
-                self.cancel();
-                true
-            } else {
-                false
-            }
-        } else {
-            false
-        }
+        self.inner.effective_level() >= FORCED
     }
 
     /// Test whether an ongoing operation should cease
@@ -134,6 +585,66 @@ impl Token {
     }
 }
 
+/// A RAII guard, created by `TokenSource::drop_guard`, that cancels its
+/// source when dropped.
+#[derive(Debug)]
+pub struct DropGuard {
+    source: Option<TokenSource>,
+}
+
+impl DropGuard {
+    /// Consume the guard and return the source without canceling it.
+    pub fn disarm(mut self) -> TokenSource {
+        self.source.take().expect("DropGuard source already taken")
+    }
+}
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        if let Some(source) = self.source.take() {
+            source.cancel();
+        }
+    }
+}
+
+/// Extension trait providing `drop_guard` for a `TokenSource` shared via
+/// `Arc`, for callers who hand out clones of the `Arc` rather than the
+/// `TokenSource` itself.
+pub trait ArcTokenSourceExt {
+    /// Consume this `Arc<TokenSource>` and return a RAII guard that
+    /// calls `cancel()` on the source when it is dropped.
+    fn drop_guard(self) -> ArcDropGuard;
+}
+
+impl ArcTokenSourceExt for Arc<TokenSource> {
+    fn drop_guard(self) -> ArcDropGuard {
+        ArcDropGuard { source: Some(self) }
+    }
+}
+
+/// A RAII guard, created via `ArcTokenSourceExt::drop_guard`, that
+/// cancels its `Arc<TokenSource>` when dropped.
+#[derive(Debug)]
+pub struct ArcDropGuard {
+    source: Option<Arc<TokenSource>>,
+}
+
+impl ArcDropGuard {
+    /// Consume the guard and return the `Arc<TokenSource>` without
+    /// canceling it.
+    pub fn disarm(mut self) -> Arc<TokenSource> {
+        self.source.take().expect("ArcDropGuard source already taken")
+    }
+}
+
+impl Drop for ArcDropGuard {
+    fn drop(&mut self) {
+        if let Some(source) = self.source.take() {
+            source.cancel();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -142,9 +653,10 @@ mod tests {
 
     #[test]
     fn it_works() {
-        let token = Token::new();
+        let source = TokenSource::new();
+        let token = source.token();
         assert!(!token.was_canceled());
-        token.cancel();
+        source.cancel();
         assert!(token.was_canceled());
     }
 
@@ -157,8 +669,9 @@ mod tests {
 
     #[test]
     fn err() {
-        let token = Token::new();
-        token.cancel();
+        let source = TokenSource::new();
+        let token = source.token();
+        source.cancel();
         assert_eq!(true, token.check_cancel().is_err());
         assert_eq!(true, check(&token).is_err());
     }
@@ -166,7 +679,7 @@ mod tests {
     #[test]
     fn deadline() {
         let hard_deadline = Instant::now() + Duration::new(2, 0);
-        let token = Token::with_duration(Duration::new(1, 0));
+        let token = TokenSource::with_duration(Duration::new(1, 0)).token();
         loop {
             if token.is_canceled() {
                 break;
@@ -179,15 +692,255 @@ mod tests {
 
     #[test]
     fn threads() {
-        let token = Arc::new(Token::with_duration(Duration::new(1, 0)));
-        let shared = Arc::clone(&token);
+        let source = Arc::new(TokenSource::with_duration(Duration::new(1, 0)));
+        let token = source.token();
         let thr = std::thread::spawn(move || {
-            while !shared.is_canceled() {
+            while !token.is_canceled() {
                 std::thread::sleep(Duration::from_millis(200));
             }
             true
         });
         assert_eq!(true, thr.join().unwrap());
-        assert_eq!(true, token.was_canceled());
+        assert!(source.token().was_canceled());
+    }
+
+    #[test]
+    fn child_token_inherits_parent_cancel() {
+        let source = TokenSource::new();
+        let parent = source.token();
+        let child = parent.child_token();
+        assert!(!child.is_canceled());
+        source.cancel();
+        assert!(child.is_canceled());
+    }
+
+    #[test]
+    fn was_canceled_sees_ancestor_cancellation() {
+        let source = TokenSource::new();
+        let parent = source.token();
+        let child = parent.child_token();
+        assert!(!child.was_canceled());
+        source.cancel();
+        assert!(child.was_canceled());
+    }
+
+    #[test]
+    fn child_source_cancel_does_not_affect_parent() {
+        let source = TokenSource::new();
+        let child_source = source.child_token_source();
+        child_source.cancel();
+        assert!(child_source.token().was_canceled());
+        assert!(!source.token().was_canceled());
+    }
+
+    #[test]
+    fn on_cancel_fires_once() {
+        use std::sync::atomic::AtomicUsize;
+
+        let source = TokenSource::new();
+        let token = source.token();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let registered = Arc::clone(&calls);
+        token.on_cancel(move || {
+            registered.fetch_add(1, Ordering::SeqCst);
+        });
+        source.cancel();
+        source.cancel();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn on_cancel_runs_immediately_if_already_canceled() {
+        use std::sync::atomic::AtomicBool as Flag;
+
+        let source = TokenSource::new();
+        source.cancel();
+        let token = source.token();
+        let ran = Arc::new(Flag::new(false));
+        let observed = Arc::clone(&ran);
+        token.on_cancel(move || observed.store(true, Ordering::SeqCst));
+        assert!(ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn on_cancel_does_not_fire_eagerly_from_ancestor_cancellation() {
+        use std::sync::atomic::AtomicBool as Flag;
+
+        let source = TokenSource::new();
+        let child = source.token().child_token();
+        let ran = Arc::new(Flag::new(false));
+        let observed = Arc::clone(&ran);
+        child.on_cancel(move || observed.store(true, Ordering::SeqCst));
+        source.cancel();
+        // Not woken: the parent keeps no bookkeeping on its children, so
+        // nothing drains `child`'s callbacks until it is polled again.
+        assert!(!ran.load(Ordering::SeqCst));
+        assert!(child.is_canceled());
+    }
+
+    #[test]
+    fn child_deadline_bounded_by_parent() {
+        let parent = TokenSource::with_duration(Duration::new(1, 0)).token();
+        let child = parent.child_token();
+        let hard_deadline = Instant::now() + Duration::new(2, 0);
+        loop {
+            if child.is_canceled() {
+                break;
+            }
+            assert!(Instant::now() < hard_deadline);
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn cancelled_resolves_on_cancel() {
+        let source = Arc::new(TokenSource::new());
+        let token = source.token();
+        let handle = tokio::spawn(async move { token.cancelled().await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        source.cancel();
+        handle.await.unwrap();
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn cancelled_resolves_immediately_if_already_canceled() {
+        let source = TokenSource::new();
+        source.cancel();
+        source.token().cancelled().await;
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn cancelled_does_not_miss_a_cancel_racing_the_registration() {
+        let source = Arc::new(TokenSource::new());
+        let token = source.token();
+        let handle = tokio::spawn(async move { token.cancelled().await });
+        // Yield just enough for the spawned task to register its waiter,
+        // then cancel immediately (no sleep): this is the narrow window
+        // in which a lost wakeup would park the future forever.
+        tokio::task::yield_now().await;
+        source.cancel();
+        tokio::time::timeout(Duration::from_secs(2), handle)
+            .await
+            .expect("cancelled() should observe the race-y cancel, not hang")
+            .unwrap();
+    }
+
+    #[test]
+    fn drop_guard_cancels_on_drop() {
+        let source = TokenSource::new();
+        let observer = source.token();
+        {
+            let _guard = source.drop_guard();
+        }
+        assert!(observer.is_canceled());
+    }
+
+    #[test]
+    fn drop_guard_disarm_avoids_cancel() {
+        let source = TokenSource::new();
+        let observer = source.token();
+        let guard = source.drop_guard();
+        let source = guard.disarm();
+        assert!(!observer.is_canceled());
+        drop(source);
+        assert!(!observer.is_canceled());
+    }
+
+    #[test]
+    fn arc_drop_guard_cancels_on_drop() {
+        let source = Arc::new(TokenSource::new());
+        let observer = source.token();
+        {
+            let _guard = source.drop_guard();
+        }
+        assert!(observer.is_canceled());
+    }
+
+    #[test]
+    fn cancel_after_arms_deadline_dynamically() {
+        let source = TokenSource::new();
+        let token = source.token();
+        assert!(!token.is_canceled());
+        source.cancel_after(Duration::new(1, 0));
+        let hard_deadline = Instant::now() + Duration::new(2, 0);
+        loop {
+            if token.is_canceled() {
+                break;
+            }
+            assert!(Instant::now() < hard_deadline);
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn graceful_then_forced_escalation() {
+        let source = TokenSource::new();
+        let token = source.token();
+        assert!(!token.is_graceful_canceled());
+        assert!(!token.is_canceled());
+
+        source.cancel_graceful();
+        assert!(token.is_graceful_canceled());
+        assert!(!token.is_canceled());
+
+        source.cancel();
+        assert!(token.is_graceful_canceled());
+        assert!(token.is_canceled());
+    }
+
+    #[test]
+    fn any_cancels_when_a_single_source_cancels() {
+        let a = TokenSource::new();
+        let b = TokenSource::new();
+        let combined = Token::any(&[a.token(), b.token()]);
+        assert!(!combined.is_canceled());
+        a.cancel();
+        assert!(combined.is_canceled());
+    }
+
+    #[test]
+    fn all_cancels_only_once_every_source_cancels() {
+        let a = TokenSource::new();
+        let b = TokenSource::new();
+        let combined = Token::all(&[a.token(), b.token()]);
+        a.cancel();
+        assert!(!combined.is_canceled());
+        b.cancel();
+        assert!(combined.is_canceled());
+    }
+
+    #[test]
+    fn any_effective_deadline_is_earliest_source() {
+        let sooner = TokenSource::with_duration(Duration::new(1, 0)).token();
+        let later = TokenSource::with_duration(Duration::new(10, 0)).token();
+        let combined = Token::any(&[sooner, later]);
+        let hard_deadline = Instant::now() + Duration::new(2, 0);
+        loop {
+            if combined.is_canceled() {
+                break;
+            }
+            assert!(Instant::now() < hard_deadline);
+            std::thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn graceful_deadline_does_not_imply_forced() {
+        let source = TokenSource::with_durations(Duration::new(0, 0), Duration::new(1, 0));
+        let token = source.token();
+        assert!(token.is_graceful_canceled());
+        assert!(!token.is_canceled());
+
+        let hard_deadline = Instant::now() + Duration::new(2, 0);
+        loop {
+            if token.is_canceled() {
+                break;
+            }
+            assert!(Instant::now() < hard_deadline);
+            std::thread::sleep(Duration::from_millis(200));
+        }
     }
 }